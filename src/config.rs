@@ -1,8 +1,9 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::process::Command as OsCommand;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use serde_derive::{Deserialize, Serialize};
 use url::Url;
 
@@ -42,12 +43,56 @@ impl Cargo {
 }
 
 #[derive(Debug, Deserialize)]
-pub struct CargoLock {
-    pub package: Option<Vec<Package>>,
+pub struct Package {
+    // Cargo.toml 中 [package] 的包名称
+    pub name: String,
+    // Cargo.toml 中 [package] 的包版本
+    pub version: String,
+}
+
+/// `cargo metadata --format-version 1` 的输出，用来替代直接解析 Cargo.lock。
+///
+/// 相比手工解析 Cargo.lock 并按 "name-version" 匹配 registry 目录名，metadata
+/// 能准确反映 feature 开关、workspace 成员以及 git / path 依赖，是依赖解析的
+/// 唯一可信来源。
+#[derive(Debug, Deserialize)]
+pub struct Metadata {
+    pub packages: Vec<MetadataPackage>,
+    pub resolve: Option<MetadataResolve>,
+    pub workspace_members: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MetadataPackage {
+    pub id: String,
+    pub name: String,
+    pub version: String,
+    pub manifest_path: PathBuf,
+    pub source: Option<String>,
+    pub edition: String,
+    pub targets: Vec<MetadataTarget>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MetadataTarget {
+    pub name: String,
+    pub kind: Vec<String>,
+    pub src_path: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MetadataResolve {
+    pub nodes: Vec<MetadataNode>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MetadataNode {
+    pub id: String,
+    pub dependencies: Vec<String>,
 }
 
-impl CargoLock {
-    /// 读取 Cargo.lock 解析成 CargoLock
+impl Metadata {
+    /// 执行 `cargo metadata` 解析项目依赖图
     ///
     /// # Example
     ///
@@ -56,34 +101,134 @@ impl CargoLock {
     ///
     /// use std::path::Path;
     /// use anyhow::Result
-    /// use config::CargoLock
+    /// use config::Metadata
     ///
     /// fn main() -> Result<()> {
-    ///     let path = Path::new("Cargo.lock");
-    ///     let cargo_lock = CargoLock::from_path(path)?;    
-    ///         
+    ///     let path = Path::new("Cargo.toml");
+    ///     let metadata = Metadata::from_manifest(path)?;
+    ///
     ///     Ok(())
     /// }
     /// ```
-    pub fn from_path<P>(path: P) -> Result<CargoLock>
+    pub fn from_manifest<P>(manifest_path: P) -> Result<Metadata>
     where
         P: AsRef<Path>,
     {
-        let s = fs::read_to_string(path)?;
-        let cargo_lock: CargoLock = toml::from_str(&s)?;
+        let output = OsCommand::new("cargo")
+            .arg("metadata")
+            .arg("--format-version")
+            .arg("1")
+            .arg("--manifest-path")
+            .arg(manifest_path.as_ref())
+            .output()?;
+
+        if !output.status.success() {
+            return Err(anyhow!(
+                "cargo metadata failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
 
-        Ok(cargo_lock)
+        let metadata: Metadata = serde_json::from_slice(&output.stdout)?;
+
+        Ok(metadata)
     }
-}
 
-#[derive(Debug, Deserialize)]
-pub struct Package {
-    // rust 第三方包名称
-    pub name: String,
-    // rust 第三方包版本
-    pub version: String,
+    /// resolve.nodes 按 package id 索引，方便反复查询某个包的依赖列表
+    fn resolve_nodes(&self) -> HashMap<&str, &Vec<String>> {
+        match &self.resolve {
+            Some(resolve) => resolve
+                .nodes
+                .iter()
+                .map(|node| (node.id.as_str(), &node.dependencies))
+                .collect(),
+            None => HashMap::new(),
+        }
+    }
+
+    /// 从 workspace 成员出发遍历 resolve 依赖图，返回实际被用到的 package id
+    fn used_package_ids(&self) -> HashSet<&str> {
+        let nodes = self.resolve_nodes();
+
+        let mut used = HashSet::new();
+        let mut queue: Vec<&str> = self.workspace_members.iter().map(|id| id.as_str()).collect();
+
+        while let Some(id) = queue.pop() {
+            if !used.insert(id) {
+                continue;
+            }
+            if let Some(deps) = nodes.get(id) {
+                queue.extend(deps.iter().map(|dep| dep.as_str()));
+            }
+        }
+
+        used
+    }
+
+    /// 被用到的所有包，包含 workspace 成员自身
+    pub fn resolved_packages(&self) -> Vec<&MetadataPackage> {
+        let used = self.used_package_ids();
+
+        self.packages
+            .iter()
+            .filter(|pack| used.contains(pack.id.as_str()))
+            .collect()
+    }
+
+    /// 被用到的第三方包（排除 workspace 成员本身）
+    fn used_dependency_packages(&self) -> Vec<&MetadataPackage> {
+        self.resolved_packages()
+            .into_iter()
+            .filter(|pack| !self.workspace_members.iter().any(|m| m == &pack.id))
+            .collect()
+    }
+
+    /// 返回被用到的本地 path 依赖，(包名, 所在目录)
+    ///
+    /// path 依赖在 cargo metadata 里没有 `source` 字段，用 `is_none()` 判断，
+    /// 不依赖 "registry+"/"git+"/"sparse+" 这类协议前缀字符串匹配，因此不会漏判
+    /// 走 sparse 协议的第三方 registry（那类包的 source 是 "sparse+https://…"）。
+    pub fn path_dependencies(&self) -> Vec<(String, PathBuf)> {
+        self.used_dependency_packages()
+            .into_iter()
+            .filter(|pack| pack.source.is_none())
+            .filter_map(|pack| {
+                pack.manifest_path
+                    .parent()
+                    .map(|dir| (pack.name.clone(), dir.to_path_buf()))
+            })
+            .collect()
+    }
+
+    /// 返回所有 workspace 成员，(包名, 所在目录)。虚拟 manifest（没有 `[package]`）下
+    /// `workspace_members` 可能包含多个成员 crate，需要分别作为工作区目录。
+    pub fn workspace_member_dirs(&self) -> Vec<(String, PathBuf)> {
+        self.packages
+            .iter()
+            .filter(|pack| self.workspace_members.iter().any(|m| m == &pack.id))
+            .filter_map(|pack| {
+                pack.manifest_path
+                    .parent()
+                    .map(|dir| (pack.name.clone(), dir.to_path_buf()))
+            })
+            .collect()
+    }
 }
 
+// 原来这里按目录名逐条生成 "files.exclude" 条目，把 registry/git 缓存下每个
+// 未被依赖的目录都单独列出来。vscode 的 `files.exclude` 是按每个 workspace
+// folder 各自的根目录做相对匹配的，并不会按 folder 的 `name` 做前缀限定，所以
+// 无法用一条 "<folder 名>/**": true 加若干取反规则把通配符限定在单个 folder
+// 内——那样的通配符会被当成每个 folder（包括 "." 项目目录本身）下的 "**" 处理，
+// 结果是把整个工作区都隐藏掉。
+//
+// 也就是说在单一共享的 settings 对象下，没有办法生成一条"只排除这个 folder 下
+// 未使用目录"的规则；唯一可行的逐条排除写法，其条目数必然随该目录下的缓存包
+// 总数增长，在缓存了大量包的机器上会产生体积夸张、应用缓慢的 settings 文件——
+// 这正是该需求想要消除的问题。因此这里不再生成这部分 "files.exclude" 条目，只
+// 保留开销恒定、真正解决 rust-analyzer 启动变慢问题的 "rust-analyzer.files.excludeDirs"
+// （见 `Workspace::from` 中对 `rust_exclude_dirs` 的使用）。
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Workspace {
     // 生成 code-workspace 中的 "folder" 配置
@@ -170,37 +315,47 @@ impl Workspace {
     ///
     /// use std::path::{Path, PathBuf};
     /// use anyhow::{Ok, Result}
-    /// use config::{CargoLock, Workspace}
+    /// use config::{Metadata, Workspace}
     ///
     /// fn main() -> Result<()> {
-    ///     let path = Path::new("Cargo.lock");
-    ///     let cargo_lock = CargoLock::from_path(path)?;
-    ///     
+    ///     let path = Path::new("Cargo.toml");
+    ///     let metadata = Metadata::from_manifest(path)?;
+    ///
     ///     let rustup = PathBuf::from_str(rustup_path);
-    ///     let registry = PathBuf::from_str(registry_path);    
-    ///     
-    ///     let ws = Workspace::from(rustup, registry, &cargo_lock)?;        
+    ///     let registry = PathBuf::from_str(registry_path);
+    ///     let git_checkouts = PathBuf::from_str(git_checkouts_path);
+    ///
+    ///     let ws = Workspace::from(rustup, registry, git_checkouts, &metadata)?;
     ///
     ///     OK(())
     /// }
     /// ```
-    pub fn from<P>(rustup: P, registry: P, lock: &CargoLock) -> Result<Workspace>
+    pub fn from<P>(rustup: P, registry: P, git_checkouts: P, metadata: &Metadata) -> Result<Workspace>
     where
         P: AsRef<Path>,
     {
         let mut folders: Vec<WorkspaceFolder> = Vec::new();
-        let mut deps = HashMap::new();
-        let mut file_excludes = HashMap::new();
         let mut rust_exclude_dirs = Vec::new();
 
-        if registry.as_ref().exists() {
-            if let Some(ref packages) = lock.package {
-                for pack in packages {
-                    let pack_name = pack.name.clone() + "-" + pack.version.as_str();
-                    deps.insert(pack_name, ());
-                }
+        // 只有一个 workspace 成员（或没有成员）时沿用根目录 "." 这个最简单的写法；
+        // 有多个成员时（例如虚拟 workspace，没有唯一的根 package）把每个成员 crate
+        // 都列成独立命名的 folder，否则根本无法区分成员与依赖。
+        let members = metadata.workspace_member_dirs();
+        if members.len() <= 1 {
+            folders.push(WorkspaceFolder {
+                name: "".to_string(),
+                path: ".".to_string(),
+            });
+        } else {
+            for (name, dir) in members {
+                folders.push(WorkspaceFolder {
+                    name,
+                    path: dir.to_string_lossy().to_string(),
+                });
             }
+        }
 
+        if registry.as_ref().exists() {
             let rustup_string = rustup.as_ref().to_path_buf().to_string_lossy().to_string();
 
             let registry_string = registry
@@ -209,20 +364,9 @@ impl Workspace {
                 .clone()
                 .to_string_lossy()
                 .to_string();
-            for p in fs::read_dir(registry.as_ref())? {
-                let entry = p.unwrap();
-                let file_name = entry.file_name().to_string_lossy().to_string();
-                if !deps.contains_key(&file_name) {
-                    file_excludes.insert(file_name.clone(), true);
-                }
-            }
 
             rust_exclude_dirs.push(registry_string.clone());
             rust_exclude_dirs.push(rustup_string.clone());
-            folders.push(WorkspaceFolder {
-                name: "".to_string(),
-                path: ".".to_string(),
-            });
             folders.push(WorkspaceFolder {
                 name: "Stdlib".to_string(),
                 path: rustup_string.clone(),
@@ -233,8 +377,36 @@ impl Workspace {
             });
         }
 
+        if git_checkouts.as_ref().exists() {
+            let git_checkouts_string = git_checkouts
+                .as_ref()
+                .to_path_buf()
+                .to_string_lossy()
+                .to_string();
+
+            rust_exclude_dirs.push(git_checkouts_string.clone());
+            folders.push(WorkspaceFolder {
+                name: "Git Dependencies".to_string(),
+                path: git_checkouts_string,
+            });
+        }
+
+        // 有意偏离最初需求里"每个新增 folder 都要追加到 rust-analyzer.files.excludeDirs"
+        // 的描述：path 依赖通常是本项目或同一工作区旁边的本地包，本来就是要浏览、修改的
+        // 代码，不是像 registry/git 缓存那样的无关依赖，屏蔽掉反而会让 rust-analyzer 无法
+        // 提供跳转、补全。因此这里只加入 folder 供浏览编辑，不加入 rust_exclude_dirs，
+        // 让 rust-analyzer 正常索引。
+        for (name, dir) in metadata.path_dependencies() {
+            folders.push(WorkspaceFolder {
+                name,
+                path: dir.to_string_lossy().to_string(),
+            });
+        }
+
+        // 不再逐个目录生成 "files.exclude"（原因见 Workspace 之前的说明），
+        // 保留该字段只是为了兼容手工在生成后的 *.code-workspace 里追加排除规则。
         let settings = WorkspaceSettings {
-            file_excludes: Some(file_excludes),
+            file_excludes: Some(HashMap::new()),
             rust_exclude_dirs: Some(rust_exclude_dirs),
         };
         let ws = Workspace {
@@ -251,18 +423,19 @@ impl Workspace {
     ///
     /// use std::path::{Path, PathBuf};
     /// use anyhow::{Ok, Result}
-    /// use config::{CargoLock, Workspace}
+    /// use config::{Metadata, Workspace}
     ///
     /// fn main() -> Result<()> {
-    ///     let path = Path::new("Cargo.lock");
-    ///     let cargo_lock = CargoLock::from_path(path)?;
-    ///     
+    ///     let path = Path::new("Cargo.toml");
+    ///     let metadata = Metadata::from_manifest(path)?;
+    ///
     ///     let rustup = PathBuf::from_str(rustup_path);
-    ///     let registry = PathBuf::from_str(registry_path);    
-    ///     
-    ///     let ws = Workspace::from(rustup, registry, &cargo_lock)?;
+    ///     let registry = PathBuf::from_str(registry_path);
+    ///     let git_checkouts = PathBuf::from_str(git_checkouts_path);
+    ///
+    ///     let ws = Workspace::from(rustup, registry, git_checkouts, &metadata)?;
     ///     let target = "simple.code-workspace";
-    ///     ws.apply(target.to_string())?;      
+    ///     ws.apply(target.to_string())?;
     ///
     ///     OK(())
     /// }
@@ -282,10 +455,11 @@ pub struct WorkspaceFolder {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct WorkspaceSettings {
-    // 因为 vscode workspace 配置文件中不支持多级目录，
-    // 如果要实现和 Clion 相同的功能，需要改变思路，先
-    // 在 folders 中加载本地所有包，再使用 "files.exclude"
-    // 忽略非本项目的其他包。
+    // 目前恒为空 map：逐包生成 "files.exclude" 排除条目的写法，在缓存了大量包的
+    // 机器上会产生随缓存总量增长的巨大 settings 文件，而 vscode 的 files.exclude
+    // 又无法按 folder 限定通配符（见 Workspace 之前的说明），所以这里不再生成。
+    // 真正需要的"别索引不相关依赖"效果由下面的 rust-analyzer.files.excludeDirs 提供，
+    // 该字段只保留给手工编辑生成后的 *.code-workspace 使用。
     #[serde(rename = "files.exclude")]
     file_excludes: Option<HashMap<String, bool>>,
 
@@ -297,12 +471,125 @@ pub struct WorkspaceSettings {
     rust_exclude_dirs: Option<Vec<String>>,
 }
 
+/// rust-analyzer 可以直接消费的 `rust-project.json`，作为 `*.code-workspace` 之外
+/// 的第二种输出格式，适用于非 cargo 或混合构建的项目。
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RustProject {
+    pub sysroot_src: String,
+    pub crates: Vec<RustProjectCrate>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RustProjectCrate {
+    pub root_module: String,
+    pub edition: String,
+    pub deps: Vec<RustProjectDep>,
+    pub is_workspace_member: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RustProjectDep {
+    #[serde(rename = "crate")]
+    pub krate: usize,
+    pub name: String,
+}
+
+impl RustProject {
+    /// # Example
+    /// ```no_run
+    /// mod config
+    ///
+    /// use std::path::Path;
+    /// use anyhow::{Ok, Result}
+    /// use config::{Metadata, RustProject}
+    ///
+    /// fn main() -> Result<()> {
+    ///     let path = Path::new("Cargo.toml");
+    ///     let metadata = Metadata::from_manifest(path)?;
+    ///
+    ///     let sysroot_src = "library_path".to_string();
+    ///     let project = RustProject::from(sysroot_src, &metadata)?;
+    ///     project.apply("rust-project.json".to_string())?;
+    ///
+    ///     OK(())
+    /// }
+    /// ```
+    pub fn from<P>(sysroot_src: P, metadata: &Metadata) -> Result<RustProject>
+    where
+        P: AsRef<Path>,
+    {
+        let packages = metadata.resolved_packages();
+        let nodes = metadata.resolve_nodes();
+
+        let index_by_id: HashMap<&str, usize> = packages
+            .iter()
+            .enumerate()
+            .map(|(idx, pack)| (pack.id.as_str(), idx))
+            .collect();
+
+        let crates = packages
+            .iter()
+            .map(|pack| {
+                let root_module = root_module(pack)
+                    .ok_or_else(|| anyhow!("package `{}` has no lib or bin target", pack.name))?;
+
+                let deps = nodes
+                    .get(pack.id.as_str())
+                    .into_iter()
+                    .flat_map(|deps| deps.iter())
+                    .filter_map(|dep_id| index_by_id.get(dep_id.as_str()).map(|idx| *idx))
+                    .map(|idx| RustProjectDep {
+                        krate: idx,
+                        name: packages[idx].name.clone(),
+                    })
+                    .collect();
+
+                Ok(RustProjectCrate {
+                    root_module: root_module.to_string_lossy().to_string(),
+                    edition: pack.edition.clone(),
+                    deps,
+                    is_workspace_member: metadata.workspace_members.iter().any(|m| m == &pack.id),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(RustProject {
+            sysroot_src: sysroot_src.as_ref().to_string_lossy().to_string(),
+            crates,
+        })
+    }
+
+    pub fn apply(&self, path: String) -> Result<()> {
+        let text = serde_json::to_string_pretty(&self)?;
+        fs::write(path, text)?;
+        Ok(())
+    }
+}
+
+/// 取一个包的主入口模块路径：优先 lib（含 proc-macro），其次 bin
+fn root_module(pack: &MetadataPackage) -> Option<PathBuf> {
+    pack.targets
+        .iter()
+        .find(|target| {
+            target
+                .kind
+                .iter()
+                .any(|kind| kind == "lib" || kind == "proc-macro")
+        })
+        .or_else(|| {
+            pack.targets
+                .iter()
+                .find(|target| target.kind.iter().any(|kind| kind == "bin"))
+        })
+        .map(|target| target.src_path.clone())
+}
+
 mod test {
     #[allow(unused)]
     use std::path::Path;
 
     #[allow(unused)]
-    use crate::config::{Cargo, CargoCfg, CargoLock, Workspace};
+    use crate::config::{Cargo, CargoCfg, Metadata, Workspace};
 
     #[test]
     fn test_from_cargo() {
@@ -313,22 +600,23 @@ mod test {
     }
 
     #[test]
-    fn test_from_cargo_lock() {
-        let path = Path::new("Cargo.lock");
-        let cargo = CargoLock::from_path(path).unwrap();
+    fn test_from_metadata() {
+        let path = Path::new("Cargo.toml");
+        let metadata = Metadata::from_manifest(path).unwrap();
 
-        assert!(cargo.package.is_some());
+        assert!(!metadata.workspace_members.is_empty());
     }
 
     #[test]
     fn test_from_workspace() {
         let rustup = Path::new("rustup").to_path_buf();
         let registry = Path::new("registry").to_path_buf();
+        let git_checkouts = Path::new("git_checkouts").to_path_buf();
 
-        let path = Path::new("Cargo.lock");
-        let cargo = CargoLock::from_path(path).unwrap();
+        let path = Path::new("Cargo.toml");
+        let metadata = Metadata::from_manifest(path).unwrap();
 
-        let ws = Workspace::from(rustup, registry, &cargo).unwrap();
+        let ws = Workspace::from(rustup, registry, git_checkouts, &metadata).unwrap();
 
         assert!(ws.folders.is_some());
         assert!(ws.settings.is_some());
@@ -336,11 +624,16 @@ mod test {
 
     #[test]
     fn test_from_workspace_failure() {
-        let ws =
-            Workspace::from(Path::new(""), Path::new(""), &CargoLock { package: None }).unwrap();
+        let metadata = Metadata {
+            packages: Vec::new(),
+            resolve: None,
+            workspace_members: Vec::new(),
+        };
+        let ws = Workspace::from(Path::new(""), Path::new(""), Path::new(""), &metadata).unwrap();
 
-        let folders = ws.folders;
-        assert!(folders.unwrap().is_empty());
+        let folders = ws.folders.unwrap();
+        assert_eq!(folders.len(), 1);
+        assert_eq!(folders[0].path, ".");
         let settings = ws.settings.unwrap();
         assert!(settings.file_excludes.unwrap().is_empty());
         assert!(settings.rust_exclude_dirs.unwrap().is_empty());
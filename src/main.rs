@@ -2,13 +2,23 @@
 /// 
 /// 思路如下:
 ///  1. vscode 可以通过设置工作区支持打开多个目录，这样就可以将标准库和第三方库添加到工作区中。
-///  2. rust 默认标准库保存在 $HOME/.rustup 目录中，通过 `rustup default` 确认默认的 toolchains，因
-///     此标准库路径为，$HOME/.rustup/toolchains/stable-aarch64-apple-darwin/lib/rustlib/src/
+///  2. 标准库源码路径通过 `rustc --print sysroot` 获取当前生效的 sysroot 来确定（对 rustup 目录覆盖、
+///     `RUSTUP_TOOLCHAIN` 等场景同样准确），仅当 PATH 上找不到 rustc 时才退回到读取 `rustup default`，
+///     标准库路径形如 $HOME/.rustup/toolchains/stable-aarch64-apple-darwin/lib/rustlib/src/
 ///  3. rust 第三方库保存在 $HOME/.cargo 目录中，ll $HOME/.cargo/registry/src/github.com-xx 中
-///  4. $HOME/.cargo 中保存本机所有项目依赖包的缓存，所以还需要忽略无关的包，读取项目 Cargo.lock 文件，确
-///     认当前项目的依赖包，将其他包记录到 "settings" > "files.exclude"
+///  4. $HOME/.cargo 中保存本机所有项目依赖包的缓存，所以还需要忽略无关的包。执行 `cargo metadata`
+///     解析项目真实的依赖图（而非手工解析 Cargo.lock），确认当前项目的依赖包。vscode 的 files.exclude
+///     是按每个 folder 各自的根目录相对匹配的，并不会按 folder 的 name 做前缀限定，因此无法只用一条
+///     "settings" > "files.exclude" 规则把通配符限定在某一个 folder 内；按目录名逐条排除又会让该设置
+///     随本机缓存包总数（而非项目实际依赖数）膨胀，在缓存较多的机器上生成巨大的 settings 文件。所以
+///     这里不再生成 "files.exclude" 排除项，只依赖下一步的 rust-analyzer.files.excludeDirs。
 ///  5. rust-analyzer 启动时会加载工作区所有的包，导致打开缓慢，设置 "settings" > "rust-analyzer.files.excludeDirs"
-///     屏蔽非本项目的包。
+///     屏蔽非本项目的包，这个开销只随 folder 数量增长，不受缓存包总数影响。
+///  6. git 依赖缓存在 $HOME/.cargo/git/checkouts 中，需要作为独立的 folder 加入工作区，处理方式类比
+///     第 4、5 步。path 依赖通常是本项目旁边的本地包，本就是要浏览、修改的代码而非无关缓存，因此只加入
+///     folder，不计入 "files.exclude" 和 "rust-analyzer.files.excludeDirs"。
+///  7. 通过 `--format rust-project` 可以改为生成 rust-project.json，rust-analyzer 能直接消费该文件，
+///     无需 "files.exclude" 这种变通手段即可浏览依赖源码。
 /// 
 /// code-workspace 格式:
 /// {
@@ -27,10 +37,7 @@
 ///    }
 ///  ],
 ///  "settings": {
-///    "files.exclude": {
-///         "clap-3.2.0",
-///         ...
-///    },
+///    "files.exclude": {},
 ///    "rust-analyzer.files.excludeDirs": [
 ///      "$HOME/.cargo/registry/src/github.com-1ecc6299db9ec823",
 ///      "$HOME/.rustup/toolchains/stable-aarch64-apple-darwin/lib/rustlib/src/rust/library"
@@ -40,7 +47,7 @@
 mod config;
 
 use clap::Parser;
-use config::{Cargo, CargoLock, Workspace};
+use config::{Cargo, CargoCfg, Metadata, RustProject, Workspace};
 use std::{
     fs,
     path::{Path, PathBuf},
@@ -64,60 +71,143 @@ struct Ws {
     /// Name of the person to greet
     #[clap(short, long, value_parser, default_value = ".")]
     root: String,
+
+    /// Output format: a vscode *.code-workspace, or a rust-project.json for rust-analyzer
+    #[clap(short, long, value_enum, default_value_t = Format::CodeWorkspace)]
+    format: Format,
 }
 
-fn generate(args: &Ws) {
-    let cargo_path = Path::new(&args.root).join("Cargo.toml");
-    let cargo_lock_path = Path::new(&args.root).join("Cargo.lock");
+#[derive(clap::ValueEnum, Clone, Debug, PartialEq, Eq)]
+#[clap(rename_all = "kebab-case")]
+enum Format {
+    CodeWorkspace,
+    RustProject,
+}
 
-    // 读取项目 Cargo.toml 和 Cargo.lock 文件 获取项目依赖第三方包信息
-    let cargo = Cargo::from_path(cargo_path).expect("Failed to parse Cargo.toml");
-    let cargo_lock = CargoLock::from_path(cargo_lock_path).expect("Failed to parse Cargo.lock");
+impl std::fmt::Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Format::CodeWorkspace => write!(f, "code-workspace"),
+            Format::RustProject => write!(f, "rust-project"),
+        }
+    }
+}
 
-    let home = dirs::home_dir().expect("Failed to get current user home directory");
+/// 确定标准库源码路径
+///
+/// 优先用 `rustc --print sysroot` 拿到当前生效的 sysroot（对 rustup 目录覆盖、
+/// `RUSTUP_TOOLCHAIN` 等场景都准确），只有在 PATH 上找不到 rustc 时才回退到原来
+/// 读取 `rustup default` 的方式。如果 sysroot 下没有 `rust-src` 组件，返回 None。
+fn stdlib_path(home: &Path) -> Option<PathBuf> {
+    let sysroot = match OsCommand::new("rustc").arg("--print").arg("sysroot").output() {
+        Ok(output) if output.status.success() => {
+            let sysroot = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            PathBuf::from(sysroot)
+        }
+        _ => {
+            let rustup_home = home.join(".rustup");
+            if !rustup_home.exists() {
+                return None;
+            }
 
-    let rustup_home = Path::new(&home).join(".rustup");
-    if !rustup_home.exists() {
-        println!("rustc rust be installed");
-        return;
-    }
+            let output = OsCommand::new("rustup").arg("default").output().ok()?;
+            let result = String::from_utf8_lossy(&output.stdout).to_string();
+            let toolchain = result.split(' ').next()?;
+            rustup_home.join("toolchains").join(toolchain)
+        }
+    };
 
-    let output = OsCommand::new("rustup")
-        .arg("default")
-        .output()
-        .expect("Failed to execute rustup");
-    let result = String::from_utf8_lossy(output.stdout.as_slice()).to_string();
-    let toolchain = result.split(" ").take(1).next().expect("Failed to parse rustup toolchain");
-    let rustup = rustup_home
-        .join("toolchains")
-        .join(toolchain)
+    let library = sysroot
         .join("lib")
         .join("rustlib")
         .join("src")
         .join("rust")
         .join("library");
 
+    if library.exists() {
+        Some(library)
+    } else {
+        None
+    }
+}
+
+fn generate(args: &Ws) {
+    let cargo_path = Path::new(&args.root).join("Cargo.toml");
+
+    // 读取项目 Cargo.toml 获取包名，并通过 cargo metadata 解析真实的依赖图
+    let cargo = Cargo::from_path(&cargo_path).expect("Failed to parse Cargo.toml");
+    let metadata = Metadata::from_manifest(&cargo_path).expect("Failed to run cargo metadata");
+
+    let home = dirs::home_dir().expect("Failed to get current user home directory");
+
+    let stdlib = match stdlib_path(&home) {
+        Some(path) => path,
+        None => {
+            println!(
+                "Could not find the Rust standard library sources. Run `rustup component add rust-src` and try again."
+            );
+            return;
+        }
+    };
+
+    if args.format == Format::RustProject {
+        let project =
+            RustProject::from(stdlib, &metadata).expect("Failed to build rust-project.json");
+        project
+            .apply("rust-project.json".to_string())
+            .expect("Failed to save rust-project.json");
+        return;
+    }
+
     // 确定 rust .cargo 路径
     let cargo_home = Path::new(&home).join(".cargo");
     if !cargo_home.exists() {
         println!("cargo not be installed");
         return;
     }
-    let registry_src = fs::read_dir(cargo_home.join("registry").join("src").as_path())
-        .expect("Failed to walk $HOME/.cargo");
+    let registry_src_path = cargo_home.join("registry").join("src");
     let mut registry = PathBuf::new();
-    let registry_entry = registry_src.take(1).next();
-    if let Some(result) = registry_entry {
-        if let Ok(entry) = result {
+
+    // 如果 $HOME/.cargo/config.toml 配置了 source replacement（例如国内镜像），
+    // registry/src 下会出现一个按该镜像 host 命名的目录，而不是 crates.io 的默认目录，
+    // 这里优先按配置选取，避免想当然地取第一个目录。
+    let replaced_host = CargoCfg::read().ok().and_then(|cfg| cfg.registry());
+    if let Some(host) = replaced_host {
+        let matched = fs::read_dir(&registry_src_path)
+            .expect("Failed to walk $HOME/.cargo")
+            .filter_map(|entry| entry.ok())
+            .find(|entry| entry.file_name().to_string_lossy().contains(&host));
+        if let Some(entry) = matched {
             registry = entry.path();
         }
     }
 
-    let ws = Workspace::from(rustup, registry, &cargo_lock).expect("Failed to create workspace");
+    if registry.as_os_str().is_empty() {
+        let registry_entry = fs::read_dir(&registry_src_path)
+            .expect("Failed to walk $HOME/.cargo")
+            .take(1)
+            .next();
+        if let Some(result) = registry_entry {
+            if let Ok(entry) = result {
+                registry = entry.path();
+            }
+        }
+    }
+
+    let git_checkouts = cargo_home.join("git").join("checkouts");
+
+    let ws = Workspace::from(stdlib, registry, git_checkouts, &metadata)
+        .expect("Failed to create workspace");
 
+    // 虚拟 manifest（只有 [workspace]，没有 [package]）没有根包名，
+    // 退而求其次用 workspace 根目录名命名输出文件
     let name = match cargo.package {
         Some(ref pack) => pack.name.clone(),
-        None => "cargo-ws".to_string(),
+        None => Path::new(&args.root)
+            .canonicalize()
+            .ok()
+            .and_then(|root| root.file_name().map(|n| n.to_string_lossy().to_string()))
+            .unwrap_or_else(|| "cargo-ws".to_string()),
     };
 
     let path = name + ".code-workspace";